@@ -0,0 +1,132 @@
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+
+/// How serious a [`Diagnostic`] is, controlling the label and color used
+/// when it's rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[31m",
+            Severity::Warning => "\x1b[33m",
+        }
+    }
+}
+
+/// A single reported problem, pointing at the exact byte range in the
+/// original source that caused it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Range<usize>,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(message: S, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning<S: Into<String>>(message: S, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Warning,
+        }
+    }
+}
+
+/// Renders a [`Diagnostic`] against the `source` it was derived from,
+/// reproducing the offending line with a caret underneath the span and a
+/// line-number gutter, colored when stdout is a TTY.
+pub fn render(source: &str, diagnostic: &Diagnostic) -> String {
+    render_colored(source, diagnostic, io::stdout().is_terminal())
+}
+
+/// Writes the rendered form of `diagnostic` to `w`.
+pub fn emit<W: Write>(w: &mut W, source: &str, diagnostic: &Diagnostic) -> io::Result<()> {
+    write!(w, "{}", render(source, diagnostic))
+}
+
+fn render_colored(source: &str, diagnostic: &Diagnostic, color: bool) -> String {
+    let start = diagnostic.span.start.min(source.len());
+    let end = diagnostic.span.end.max(start);
+
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[line_start..]
+        .find('\n')
+        .map_or(source.len(), |i| line_start + i);
+    let line = &source[line_start..line_end];
+    let line_number = source[..line_start].matches('\n').count() + 1;
+    // Caret placement counts chars, not bytes, so multi-byte UTF-8
+    // content before or inside the span doesn't throw it off.
+    let col = source[line_start..start].chars().count();
+    let caret_len = source[start..end.min(line_end)].chars().count().max(1);
+
+    let gutter = line_number.to_string();
+    let pad = " ".repeat(gutter.len());
+    let (on, off) = if color {
+        (diagnostic.severity.color(), "\x1b[0m")
+    } else {
+        ("", "")
+    };
+
+    format!(
+        "{on}{}{off}: {}\n{pad} |\n{gutter} | {line}\n{pad} | {}{on}{}{off}\n",
+        diagnostic.severity.label(),
+        diagnostic.message,
+        " ".repeat(col),
+        "^".repeat(caret_len),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_at_the_offending_word() {
+        let source = "echo hello\nexport = world\n";
+        let diagnostic = Diagnostic::error("expected a name before '='", 18..19);
+        assert_eq!(
+            render_colored(source, &diagnostic, false),
+            "error: expected a name before '='\n  |\n2 | export = world\n  |        ^\n",
+        );
+    }
+
+    #[test]
+    fn multi_byte_chars_before_and_in_the_span_dont_shift_the_caret() {
+        let source = "echo héllo wörld\n";
+        let diagnostic = Diagnostic::error("bad word", 12..18);
+        assert_eq!(
+            render_colored(source, &diagnostic, false),
+            "error: bad word\n  |\n1 | echo héllo wörld\n  |            ^^^^^\n",
+        );
+    }
+
+    #[test]
+    fn multi_char_span_widens_the_caret() {
+        let source = "foo bar baz";
+        let diagnostic = Diagnostic::warning("unused word", 4..7);
+        assert_eq!(
+            render_colored(source, &diagnostic, false),
+            "warning: unused word\n  |\n1 | foo bar baz\n  |     ^^^\n",
+        );
+    }
+}
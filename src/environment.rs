@@ -5,7 +5,7 @@ use std::ffi::{OsStr, OsString};
 use std::path::Path;
 
 use crate::ast::{Exportable, NameValuePair};
-use crate::Result;
+use crate::diagnostic::Diagnostic;
 
 pub struct Environment {
     values: HashMap<OsString, Var>,
@@ -24,7 +24,12 @@ impl Environment {
         self.values.get(name.as_ref()).map(|var| var.value.as_ref())
     }
 
-    pub fn assign(&mut self, pair: &NameValuePair) -> Result<()> {
+    /// Assigns `pair.name` to the expansion of `pair.value`.
+    ///
+    /// Fails with a [`Diagnostic`] pointing at the offending part of the
+    /// value (e.g. an unset variable reference) instead of an opaque
+    /// error, so a typo in a script body gives an actionable pointer.
+    pub fn assign(&mut self, pair: &NameValuePair) -> Result<(), Diagnostic> {
         let value = pair.value.expand(self)?.into_owned();
         match self.values.entry(pair.name.to_os_string()) {
             Entry::Occupied(mut entry) => entry.get_mut().value = value,
@@ -35,7 +40,10 @@ impl Environment {
         Ok(())
     }
 
-    pub fn export(&mut self, exportable: &Exportable) -> Result<()> {
+    /// Exports `exportable.name`, optionally assigning it the expansion of
+    /// `exportable.value` first. See [`Environment::assign`] for the error
+    /// behavior.
+    pub fn export(&mut self, exportable: &Exportable) -> Result<(), Diagnostic> {
         if let Some(ref value) = exportable.value {
             let var = Var::new(value.expand(self)?.into_owned(), true);
             self.values.insert(exportable.name.to_os_string(), var);
@@ -0,0 +1,120 @@
+//! A canonical source formatter, built on [`Lexer::new_lossless`].
+//!
+//! Re-emits a script with consistent two-space indentation of `{ }`
+//! blocks, one statement per line, and normalized spacing between words,
+//! while preserving comments.
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Kind, Lexer};
+
+const INDENT: &str = "  ";
+
+/// Formats `source` canonically.
+///
+/// Refuses to run on a script the lexer couldn't fully tokenize (e.g. an
+/// unterminated quote), returning the collected diagnostics instead of
+/// silently dropping the unrecognized bytes from the output.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut out = String::new();
+    let mut indent = 0usize;
+    let mut line_has_content = false;
+
+    let mut lexer = Lexer::new_lossless(source);
+    for token in lexer.by_ref() {
+        match token.kind {
+            Kind::Trivia(ref text) => {
+                let comment = text.trim();
+                if !comment.starts_with('#') {
+                    // Normalize away everything but comments: extra
+                    // spacing, blank lines, etc. A line break still ends
+                    // a line that holds only a leading comment, so the
+                    // next statement isn't appended to it.
+                    if text.contains('\n') && line_has_content {
+                        out.push('\n');
+                        line_has_content = false;
+                    }
+                    continue;
+                }
+                push_word(&mut out, &mut line_has_content, indent, comment);
+            }
+            Kind::Word(_) => {
+                push_word(&mut out, &mut line_has_content, indent, &source[token.span]);
+            }
+            Kind::LeftBrace => {
+                push_word(&mut out, &mut line_has_content, indent, "{");
+                indent += 1;
+                out.push('\n');
+                line_has_content = false;
+            }
+            Kind::RightBrace => {
+                indent = indent.saturating_sub(1);
+                if line_has_content {
+                    out.push('\n');
+                    line_has_content = false;
+                }
+                push_word(&mut out, &mut line_has_content, indent, "}");
+            }
+            Kind::Semi => {
+                if line_has_content {
+                    out.push('\n');
+                    line_has_content = false;
+                }
+            }
+        }
+    }
+
+    if lexer.diagnostics().is_empty() {
+        Ok(out)
+    } else {
+        Err(lexer.diagnostics().to_vec())
+    }
+}
+
+fn push_word(out: &mut String, line_has_content: &mut bool, indent: usize, word: &str) {
+    if *line_has_content {
+        out.push(' ');
+    } else {
+        out.push_str(&INDENT.repeat(indent));
+    }
+    out.push_str(word);
+    *line_has_content = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_spacing_and_indentation() {
+        let src = "if   true {\necho    hi\n}\n";
+        assert_eq!(format(src).unwrap(), "if true {\n  echo hi\n}\n");
+    }
+
+    #[test]
+    fn preserves_comments() {
+        let src = "echo hi # greeting\n";
+        assert_eq!(format(src).unwrap(), "echo hi # greeting\n");
+    }
+
+    #[test]
+    fn collapses_blank_lines() {
+        let src = "echo a\n\n\necho b\n";
+        assert_eq!(format(src).unwrap(), "echo a\necho b\n");
+    }
+
+    #[test]
+    fn formatting_already_formatted_input_is_idempotent() {
+        let src = "if true {\n  echo hi\n} # trailing\n";
+        let once = format(src).unwrap();
+        let twice = format(&once).unwrap();
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn refuses_to_format_a_script_with_an_unterminated_quote() {
+        let src = "echo 'oops\necho should_still_appear\n";
+        let diagnostics = format(src).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, 5..10);
+    }
+}
@@ -1,55 +1,208 @@
+//! Tokenizes msh source, recovering from malformed tokens (e.g. an
+//! unterminated quote) by resynchronizing at the next line terminator so
+//! later, independent errors are still found in the same pass. Only lexing
+//! errors are collected here; this module does no parser-side recovery.
+
+use std::collections::VecDeque;
 use std::fmt;
+use std::ops::Range;
 use std::str::Chars;
 
+use crate::diagnostic::Diagnostic;
+
 pub struct Lexer<'input> {
+    input: &'input str,
     src: Chars<'input>,
     line: usize,
+    col: usize,
+    offset: usize,
+    prev_col: usize,
     peek: Option<char>,
-    next: Option<Kind>,
+    pending: VecDeque<Token>,
     last: Option<Kind>,
+    diagnostics: Vec<Diagnostic>,
+    lossless: bool,
 }
 
 impl<'input> Lexer<'input> {
     pub fn new(src: &'input str) -> Self {
         Self {
+            input: src,
             src: src.chars(),
             line: 1,
+            col: 1,
+            offset: 0,
+            prev_col: 1,
             peek: None,
-            next: None,
+            pending: VecDeque::new(),
             last: None,
+            diagnostics: Vec::new(),
+            lossless: false,
+        }
+    }
+
+    /// Like [`Lexer::new`], but never discards whitespace, blank lines, or
+    /// comments: they're emitted as `Kind::Trivia` tokens interleaved with
+    /// the real ones, so concatenating every token's source slice
+    /// (`&source[token.span]`) in order reconstructs `src` exactly.
+    pub fn new_lossless(src: &'input str) -> Self {
+        Self {
+            lossless: true,
+            ..Self::new(src)
         }
     }
 
-    fn emit(&mut self, kind: Kind, line: Option<usize>) -> Option<Token> {
-        self.last = Some(kind.clone());
-        Some(Token::new(kind, line.unwrap_or(self.line)))
+    /// Diagnostics accumulated so far, e.g. from unterminated quotes.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn pos(&self) -> Pos {
+        Pos {
+            offset: self.offset,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    fn emit(&mut self, kind: Kind, start: Pos, end: usize) -> Option<Token> {
+        if !matches!(kind, Kind::Trivia(_)) {
+            self.last = Some(kind.clone());
+        }
+        Some(Token::new(kind, start.line, start.col, start.offset..end))
+    }
+
+    fn trivia(&self, start: Pos, end: usize) -> Token {
+        Token::new(
+            Kind::Trivia(self.input[start.offset..end].to_string()),
+            start.line,
+            start.col,
+            start.offset..end,
+        )
     }
 
     fn next_char(&mut self) -> Option<char> {
         let next = self.peek.take().or_else(|| self.src.next());
-        if next == Some('\n') {
-            self.line += 1;
+        if let Some(c) = next {
+            self.offset += c.len_utf8();
+            self.prev_col = self.col;
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
         }
         next
     }
 
     fn push_char(&mut self, c: char) {
         assert!(self.peek.is_none());
+        self.offset -= c.len_utf8();
         if c == '\n' {
             self.line -= 1;
         }
+        self.col = self.prev_col;
         self.peek = Some(c);
     }
 
+    /// Consumes a run of line terminators, returning the offset just past
+    /// the last one consumed.
     fn consume_line_terminators(&mut self) -> usize {
-        let line = self.line;
         while let Some(c) = self.next_char() {
             if !is_line_terminator(c) {
                 self.push_char(c);
                 break;
             }
         }
-        line
+        self.pos().offset
+    }
+
+    /// Discards a `#` line comment, stopping just before the line
+    /// terminator that ends it (or at end-of-input).
+    fn consume_comment(&mut self) {
+        while let Some(c) = self.next_char() {
+            if is_line_terminator(c) {
+                self.push_char(c);
+                break;
+            }
+        }
+    }
+
+    /// Consumes a run of non-newline whitespace, returning the offset
+    /// just past the last one consumed.
+    fn consume_whitespace_run(&mut self) -> usize {
+        while let Some(c) = self.next_char() {
+            if !c.is_whitespace() || is_line_terminator(c) {
+                self.push_char(c);
+                break;
+            }
+        }
+        self.pos().offset
+    }
+
+    /// Recovers from a malformed token (e.g. an unterminated quote) by
+    /// discarding input up to and including the next line terminator,
+    /// returning the offset just past it, so tokenization can resume and
+    /// later, independent errors can still be found in the same pass.
+    fn resynchronize(&mut self) -> usize {
+        while let Some(c) = self.next_char() {
+            if is_line_terminator(c) {
+                break;
+            }
+        }
+        self.pos().offset
+    }
+
+    /// Consumes a single-quoted run into `buf`, taking every character
+    /// literally (no escapes) up to the closing `'`. Returns `false`,
+    /// without consuming it, if a raw newline or end-of-input is reached
+    /// first, so the caller can resynchronize instead of scanning the
+    /// rest of the file as string content.
+    fn consume_single_quoted(&mut self, buf: &mut String) -> bool {
+        loop {
+            match self.next_char() {
+                Some('\'') => return true,
+                Some('\n') => {
+                    self.push_char('\n');
+                    return false;
+                }
+                Some(c) => buf.push(c),
+                None => return false,
+            }
+        }
+    }
+
+    /// Consumes a double-quoted run into `buf`, unescaping `\"`, `\\`, and
+    /// `\n` but otherwise taking characters literally up to the closing
+    /// `"`. Returns `false`, without consuming it, if a raw newline or
+    /// end-of-input is reached first, so the caller can resynchronize
+    /// instead of scanning the rest of the file as string content.
+    fn consume_double_quoted(&mut self, buf: &mut String) -> bool {
+        loop {
+            match self.next_char() {
+                Some('"') => return true,
+                Some('\\') => match self.next_char() {
+                    Some('"') => buf.push('"'),
+                    Some('\\') => buf.push('\\'),
+                    Some('n') => buf.push('\n'),
+                    Some(c) => {
+                        buf.push('\\');
+                        buf.push(c);
+                    }
+                    None => {
+                        buf.push('\\');
+                        return false;
+                    }
+                },
+                Some('\n') => {
+                    self.push_char('\n');
+                    return false;
+                }
+                Some(c) => buf.push(c),
+                None => return false,
+            }
+        }
     }
 }
 
@@ -57,38 +210,138 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(kind) = self.next.take() {
-            return self.emit(kind, None);
+        if let Some(token) = self.pending.pop_front() {
+            if !matches!(token.kind, Kind::Trivia(_)) {
+                self.last = Some(token.kind.clone());
+            }
+            return Some(token);
         }
 
         let mut buf = String::new();
+        let mut start = self.pos();
+        let mut word_end = start.offset;
+
+        loop {
+            if buf.is_empty() {
+                start = self.pos();
+            }
+            let char_start = self.pos();
+
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => break,
+            };
+
+            if c == '\'' {
+                if !self.consume_single_quoted(&mut buf) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "unterminated single-quoted string",
+                        char_start.offset..self.pos().offset,
+                    ));
+                    let resync_end = self.resynchronize();
+                    if self.lossless {
+                        let kind = Kind::Trivia(self.input[start.offset..resync_end].into());
+                        return self.emit(kind, start, resync_end);
+                    }
+                    buf.clear();
+                    continue;
+                }
+                word_end = self.pos().offset;
+                continue;
+            }
+
+            if c == '"' {
+                if !self.consume_double_quoted(&mut buf) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "unterminated double-quoted string",
+                        char_start.offset..self.pos().offset,
+                    ));
+                    let resync_end = self.resynchronize();
+                    if self.lossless {
+                        let kind = Kind::Trivia(self.input[start.offset..resync_end].into());
+                        return self.emit(kind, start, resync_end);
+                    }
+                    buf.clear();
+                    continue;
+                }
+                word_end = self.pos().offset;
+                continue;
+            }
+
+            if c == '\\' {
+                match self.next_char() {
+                    Some(escaped) => buf.push(escaped),
+                    None => buf.push('\\'),
+                }
+                word_end = self.pos().offset;
+                continue;
+            }
 
-        while let Some(c) = self.next_char() {
             if buf.is_empty() {
                 if c == '{' {
-                    let line = self.consume_line_terminators();
-                    return self.emit(Kind::LeftBrace, Some(line));
+                    let after = self.pos();
+                    let trivia_end = self.consume_line_terminators();
+                    if self.lossless && trivia_end > after.offset {
+                        self.pending.push_back(self.trivia(after, trivia_end));
+                    }
+                    return self.emit(Kind::LeftBrace, start, after.offset);
                 }
                 if c == '}' {
-                    let kind = if self.last != Some(Kind::Semi) {
-                        self.next = Some(Kind::RightBrace);
-                        Kind::Semi
+                    let end = self.pos().offset;
+                    if self.last != Some(Kind::Semi) {
+                        self.pending.push_back(Token::new(
+                            Kind::RightBrace,
+                            start.line,
+                            start.col,
+                            start.offset..end,
+                        ));
+                        self.last = Some(Kind::Semi);
+                        return Some(Token::new(
+                            Kind::Semi,
+                            start.line,
+                            start.col,
+                            start.offset..start.offset,
+                        ));
                     } else {
-                        Kind::RightBrace
-                    };
-                    return self.emit(kind, None);
+                        return self.emit(Kind::RightBrace, start, end);
+                    }
+                }
+                if c == '#' {
+                    self.consume_comment();
+                    if self.lossless {
+                        let end = self.pos().offset;
+                        return self.emit(
+                            Kind::Trivia(self.input[start.offset..end].into()),
+                            start,
+                            end,
+                        );
+                    }
+                    continue;
                 }
             }
 
             if is_line_terminator(c) {
                 if buf.is_empty() {
-                    let line = self.consume_line_terminators();
-                    return if self.last.is_none() {
-                        // Don't emit leading delimiters.
-                        self.next()
-                    } else {
-                        self.emit(Kind::Semi, Some(line - 1))
-                    };
+                    let after = self.pos();
+                    if self.last.is_none() {
+                        // Don't emit leading delimiters, but keep their
+                        // bytes around as trivia when lossless.
+                        let trivia_end = self.consume_line_terminators();
+                        return if self.lossless {
+                            self.emit(
+                                Kind::Trivia(self.input[start.offset..trivia_end].into()),
+                                start,
+                                trivia_end,
+                            )
+                        } else {
+                            self.next()
+                        };
+                    }
+                    let trivia_end = self.consume_line_terminators();
+                    if self.lossless && trivia_end > after.offset {
+                        self.pending.push_back(self.trivia(after, trivia_end));
+                    }
+                    return self.emit(Kind::Semi, start, after.offset);
                 } else {
                     self.push_char(c);
                     break;
@@ -97,15 +350,25 @@ impl<'input> Iterator for Lexer<'input> {
 
             if c.is_whitespace() {
                 if buf.is_empty() {
+                    if self.lossless {
+                        let end = self.consume_whitespace_run();
+                        return self.emit(
+                            Kind::Trivia(self.input[start.offset..end].into()),
+                            start,
+                            end,
+                        );
+                    }
                     // Ignore consecutive whitespace.
                     continue;
                 } else {
                     // At the end of a token.
+                    self.push_char(c);
                     break;
                 }
             }
 
             buf.push(c);
+            word_end = self.pos().offset;
         }
 
         if buf.is_empty() {
@@ -114,10 +377,10 @@ impl<'input> Iterator for Lexer<'input> {
             if self.last == Some(Kind::Semi) {
                 None
             } else {
-                self.emit(Kind::Semi, None)
+                self.emit(Kind::Semi, start, start.offset)
             }
         } else {
-            self.emit(Kind::Word(buf), None)
+            self.emit(Kind::Word(buf), start, word_end)
         }
     }
 }
@@ -126,15 +389,33 @@ fn is_line_terminator(c: char) -> bool {
     c == '\n' || c == ';'
 }
 
+/// A source position, tracked as the lexer consumes characters.
+///
+/// `offset` is a byte offset into the original source, while `line`/`col`
+/// are 1-indexed and meant for human-facing diagnostics.
+#[derive(Clone, Copy)]
+struct Pos {
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub kind: Kind,
     pub line: usize,
+    pub col: usize,
+    pub span: Range<usize>,
 }
 
 impl Token {
-    fn new(kind: Kind, line: usize) -> Self {
-        Self { kind, line }
+    fn new(kind: Kind, line: usize, col: usize, span: Range<usize>) -> Self {
+        Self {
+            kind,
+            line,
+            col,
+            span,
+        }
     }
 }
 
@@ -144,6 +425,10 @@ pub enum Kind {
     LeftBrace,
     RightBrace,
     Semi,
+    /// Whitespace, blank lines, a comment, or input skipped while recovering
+    /// from a malformed token, only produced by a lossless lexer (see
+    /// [`Lexer::new_lossless`]). Never meaningful to a parser.
+    Trivia(String),
 }
 
 impl fmt::Display for Kind {
@@ -153,6 +438,7 @@ impl fmt::Display for Kind {
             Kind::LeftBrace => "{",
             Kind::RightBrace => "}",
             Kind::Semi => ";",
+            Kind::Trivia(ref trivia) => trivia,
         };
 
         write!(f, "'{}'", s)
@@ -229,43 +515,223 @@ mod tests {
   echo c
 }
 "#;
-        let tokens: Vec<Token> = Lexer::new(src).collect();
+        let tokens: Vec<(Kind, usize)> = Lexer::new(src).map(|t| (t.kind, t.line)).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Kind::Word("if".into()), 1),
+                (Kind::Word("/bin/a".into()), 1),
+                (Kind::LeftBrace, 1),
+                (Kind::Word("echo".into()), 2),
+                (Kind::Word("a".into()), 2),
+                (Kind::Semi, 2),
+                (Kind::RightBrace, 3),
+                (Kind::Word("else".into()), 3),
+                (Kind::Word("if".into()), 3),
+                (Kind::Word("/bin/b".into()), 3),
+                (Kind::LeftBrace, 3),
+                (Kind::Word("echo".into()), 4),
+                (Kind::Word("b".into()), 4),
+                (Kind::Semi, 4),
+                (Kind::Word("echo".into()), 5),
+                (Kind::Word("2".into()), 5),
+                (Kind::Semi, 5),
+                (Kind::Word("if".into()), 6),
+                (Kind::Word("true".into()), 6),
+                (Kind::LeftBrace, 6),
+                (Kind::Word("exit".into()), 7),
+                (Kind::Semi, 7),
+                (Kind::RightBrace, 8),
+                (Kind::Semi, 8),
+                (Kind::RightBrace, 9),
+                (Kind::Word("else".into()), 9),
+                (Kind::LeftBrace, 9),
+                (Kind::Word("echo".into()), 10),
+                (Kind::Word("c".into()), 10),
+                (Kind::Semi, 10),
+                (Kind::RightBrace, 11),
+                (Kind::Semi, 11),
+            ],
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let tokens: Vec<Token> = Lexer::new("foo bar\nbaz").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(Kind::Word("foo".into()), 1, 1, 0..3),
+                Token::new(Kind::Word("bar".into()), 1, 5, 4..7),
+                Token::new(Kind::Semi, 1, 8, 7..8),
+                Token::new(Kind::Word("baz".into()), 2, 1, 8..11),
+                Token::new(Kind::Semi, 2, 4, 11..11),
+            ],
+        );
+    }
+
+    #[test]
+    fn span_around_braces() {
+        let tokens: Vec<Token> = Lexer::new("if x { }").collect();
         assert_eq!(
             tokens,
             vec![
-                Token::new(Kind::Word("if".into()), 1),
-                Token::new(Kind::Word("/bin/a".into()), 1),
-                Token::new(Kind::LeftBrace, 1),
-                Token::new(Kind::Word("echo".into()), 2),
-                Token::new(Kind::Word("a".into()), 2),
-                Token::new(Kind::Semi, 2),
-                Token::new(Kind::RightBrace, 3),
-                Token::new(Kind::Word("else".into()), 3),
-                Token::new(Kind::Word("if".into()), 3),
-                Token::new(Kind::Word("/bin/b".into()), 3),
-                Token::new(Kind::LeftBrace, 3),
-                Token::new(Kind::Word("echo".into()), 4),
-                Token::new(Kind::Word("b".into()), 4),
-                Token::new(Kind::Semi, 4),
-                Token::new(Kind::Word("echo".into()), 5),
-                Token::new(Kind::Word("2".into()), 5),
-                Token::new(Kind::Semi, 5),
-                Token::new(Kind::Word("if".into()), 6),
-                Token::new(Kind::Word("true".into()), 6),
-                Token::new(Kind::LeftBrace, 6),
-                Token::new(Kind::Word("exit".into()), 7),
-                Token::new(Kind::Semi, 7),
-                Token::new(Kind::RightBrace, 8),
-                Token::new(Kind::Semi, 8),
-                Token::new(Kind::RightBrace, 9),
-                Token::new(Kind::Word("else".into()), 9),
-                Token::new(Kind::LeftBrace, 9),
-                Token::new(Kind::Word("echo".into()), 10),
-                Token::new(Kind::Word("c".into()), 10),
-                Token::new(Kind::Semi, 10),
-                Token::new(Kind::RightBrace, 11),
-                Token::new(Kind::Semi, 11),
+                Token::new(Kind::Word("if".into()), 1, 1, 0..2),
+                Token::new(Kind::Word("x".into()), 1, 4, 3..4),
+                Token::new(Kind::LeftBrace, 1, 6, 5..6),
+                Token::new(Kind::Semi, 1, 8, 7..7),
+                Token::new(Kind::RightBrace, 1, 8, 7..8),
+                Token::new(Kind::Semi, 1, 9, 8..8),
             ],
         );
     }
+
+    #[test]
+    fn double_quoted_word_keeps_whitespace() {
+        let tokens: Vec<Kind> = Lexer::new(r#"echo "hello world""#)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("hello world".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn quotes_join_the_surrounding_word() {
+        let tokens: Vec<Kind> = Lexer::new(r#"foo"a b"bar 'c;d'"#).map(|t| t.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("fooa bbar".into()),
+                Kind::Word("c;d".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn double_quoted_escapes() {
+        let tokens: Vec<Kind> = Lexer::new(r#""a\"b\\c\nd""#).map(|t| t.kind).collect();
+        assert_eq!(tokens, vec![Kind::Word("a\"b\\c\nd".into()), Kind::Semi],);
+    }
+
+    #[test]
+    fn single_quotes_do_not_process_escapes() {
+        let tokens: Vec<Kind> = Lexer::new(r#"'a\nb'"#).map(|t| t.kind).collect();
+        assert_eq!(tokens, vec![Kind::Word("a\\nb".into()), Kind::Semi]);
+    }
+
+    #[test]
+    fn backslash_escapes_a_single_char() {
+        let tokens: Vec<Kind> = Lexer::new(r"foo\;bar\ baz").map(|t| t.kind).collect();
+        assert_eq!(tokens, vec![Kind::Word("foo;bar baz".into()), Kind::Semi],);
+    }
+
+    #[test]
+    fn unterminated_double_quote_reports_a_diagnostic_and_keeps_lexing() {
+        let mut lexer = Lexer::new(r#"echo "hello"#);
+        let tokens: Vec<Kind> = lexer.by_ref().map(|t| t.kind).collect();
+        assert_eq!(tokens, vec![Kind::Word("echo".into()), Kind::Semi]);
+        assert_eq!(lexer.diagnostics().len(), 1);
+        assert_eq!(lexer.diagnostics()[0].span, 5..11);
+    }
+
+    #[test]
+    fn two_independent_unterminated_quotes_each_report_a_diagnostic() {
+        let mut lexer = Lexer::new("echo 'first\necho \"second\n");
+        let tokens: Vec<Kind> = lexer.by_ref().map(|t| t.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("echo".into()),
+                Kind::Semi
+            ],
+        );
+        assert_eq!(lexer.diagnostics().len(), 2);
+        assert_eq!(lexer.diagnostics()[0].span, 5..11);
+        assert_eq!(lexer.diagnostics()[1].span, 17..24);
+    }
+
+    #[test]
+    fn lossless_mode_preserves_bytes_discarded_by_quote_recovery() {
+        let src = "echo 'oops\necho should_still_appear\n";
+        let mut lexer = Lexer::new_lossless(src);
+        let mut reconstructed = String::new();
+        for token in lexer.by_ref() {
+            reconstructed.push_str(&src[token.span]);
+        }
+        assert_eq!(reconstructed, src);
+        assert_eq!(lexer.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn line_comment_is_discarded() {
+        let tokens: Vec<Kind> = Lexer::new("echo hi # greeting\necho bye")
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("hi".into()),
+                Kind::Semi,
+                Kind::Word("echo".into()),
+                Kind::Word("bye".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn hash_mid_word_is_not_a_comment() {
+        let tokens: Vec<Kind> = Lexer::new("echo foo#bar").map(|t| t.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("foo#bar".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn comment_at_end_of_input_without_trailing_newline() {
+        let tokens: Vec<Kind> = Lexer::new("echo hi # trailing").map(|t| t.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("hi".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn lossless_mode_keeps_the_same_meaningful_tokens() {
+        let src = "  if true { # greeting\n  echo hi\n}\n\n";
+        let strict: Vec<Kind> = Lexer::new(src).map(|t| t.kind).collect();
+        let lossless: Vec<Kind> = Lexer::new_lossless(src)
+            .map(|t| t.kind)
+            .filter(|kind| !matches!(kind, Kind::Trivia(_)))
+            .collect();
+        assert_eq!(strict, lossless);
+    }
+
+    #[test]
+    fn lossless_mode_round_trips_every_byte() {
+        let src = "  if true { # greeting\n  echo hi\n} # trailing\n\nfoo\"a b\"bar\n";
+        let mut reconstructed = String::new();
+        for token in Lexer::new_lossless(src) {
+            reconstructed.push_str(&src[token.span]);
+        }
+        assert_eq!(reconstructed, src);
+    }
 }